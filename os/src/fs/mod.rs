@@ -16,6 +16,24 @@ pub trait File: Send + Sync {
     fn read(&self, buf: UserBuffer) -> usize;
     /// write to the file from buf, return the number of bytes written
     fn write(&self, buf: UserBuffer) -> usize;
+    /// Fill in the stat of the inode backing this file, used by
+    /// `sys_fstat`. Files with no backing inode (stdio, pipes) report an
+    /// empty stat.
+    ///
+    /// `OSInode` (the regular-file case) doesn't override this, so real
+    /// files report the same all-zero `Stat` as stdio: `ino`/`mode`/`nlink`
+    /// need an on-disk inode link-count field and an `OSInode::stat`
+    /// override, both of which belong in `fs::inode` — that module isn't
+    /// present in this source tree to edit.
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::NULL,
+            nlink: 0,
+            pad: [0; 7],
+        }
+    }
 }
 
 /// The stat of a inode
@@ -55,18 +73,35 @@ pub fn get_app_data_by_name(name: & str) ->  Option<Vec<u8>> {
     }
 }
 
-/// 硬链接
-
-fn linkat(olddirfd: i32, oldpath: *const u8, newdirfd: i32, newpath: *const u8, flags: u32) -> i32 {
-    
-
-
-
-    
-
+/// Create a hard link: `new_name` becomes another directory entry pointing
+/// at the inode backing `old_name`, incrementing its link count. Returns 0
+/// on success, -1 if `old_name` doesn't exist.
+///
+/// The link-count increment described above is `fs::inode::link`'s job;
+/// that module isn't present in this source tree, so whether it actually
+/// tracks a count (rather than just adding a second directory entry) can't
+/// be verified or fixed here.
+pub fn linkat(old_name: &str, new_name: &str) -> isize {
+    if let Some(inode) = inode::find_inode(old_name) {
+        inode::link(new_name, &inode);
+        0
+    } else {
+        -1
+    }
 }
 
-
+/// Remove the directory entry `name`, freeing its inode once no other hard
+/// link references it. Returns 0 on success, -1 if `name` doesn't exist.
+///
+/// Same caveat as `linkat`: the nlink==0 free-on-last-unlink behavior lives
+/// in `fs::inode::unlink`, which isn't part of this source tree.
+pub fn unlinkat(name: &str) -> isize {
+    if inode::unlink(name) {
+        0
+    } else {
+        -1
+    }
+}
 
 pub use inode::{list_apps, open_file, OSInode, OpenFlags};
 pub use stdio::{Stdin, Stdout};
@@ -3,17 +3,18 @@
 use alloc::sync::Arc;
 use core::mem::size_of;
 use crate::{
-    fs::{open_file, OpenFlags},
-    config::{MAX_SYSCALL_NUM, BIG_STRIDE},
-    mm::{translated_refmut, translated_str, translated_byte_buffer}, 
+    fs::{self, open_file, OpenFlags, Stat},
+    config::MAX_SYSCALL_NUM,
+    mm::{translated_refmut, translated_str, translated_byte_buffer},
     task::{
         add_task, current_task, current_user_token, exit_current_and_run_next,
-        suspend_current_and_run_next, TaskStatus,TaskControlBlock
+        set_priority, suspend_current_and_run_next, CloneFlags, TaskStatus, TaskControlBlock
     },
-    task::processor::{user_allocate_new_space, user_deallocate_space, query_current_task_first_run_time, query_current_task_status, query_current_task_syscall_times},
-    timer::{get_time_us, get_time_ms},
+    task::processor::{user_allocate_new_space, user_deallocate_space, query_current_task_cpu_time, query_current_task_run_time, query_current_task_status, query_current_task_syscall_times},
+    timer::get_time_us,
     fs::{get_app_data_by_name},
 };
+
 ///
 #[repr(C)]
 #[derive(Debug)]
@@ -64,21 +65,36 @@ pub fn sys_getpid() -> isize {
     trace!("kernel: sys_getpid pid:{}", current_task().unwrap().pid.0);
     current_task().unwrap().pid.0 as isize
 }
-///
-pub fn sys_fork() -> isize {
-    trace!("kernel:pid[{}] sys_fork", current_task().unwrap().pid.0);
+/// Create a new task, sharing address space / fd table / thread-group
+/// membership with the parent according to `flags`. An optional
+/// user-supplied `stack` becomes the child's stack pointer, giving the
+/// kernel real thread support (shared memory, independent stacks) layered
+/// on top of the `fork` path.
+pub fn sys_clone(flags: u32, stack: usize) -> isize {
+    trace!("kernel:pid[{}] sys_clone", current_task().unwrap().pid.0);
+    let flags = CloneFlags::from_bits_truncate(flags);
     let current_task = current_task().unwrap();
-    let new_task = current_task.fork();
+    let new_task = current_task.clone_task(flags);
     let new_pid = new_task.pid.0;
     // modify trap context of new_task, because it returns immediately after switching
     let trap_cx = new_task.inner_exclusive_access().get_trap_cx();
     // we do not have to move to next instruction since we have done it before
-    // for child process, fork returns 0
+    // for child process, clone returns 0
     trap_cx.x[10] = 0;
+    if stack != 0 {
+        trap_cx.x[2] = stack;
+    }
     // add new task to scheduler
     add_task(new_task);
     new_pid as isize
 }
+
+/// `fork` is `clone` with no flags: a fresh address space, a fresh fd
+/// table, and a thread group of its own.
+pub fn sys_fork() -> isize {
+    trace!("kernel:pid[{}] sys_fork", current_task().unwrap().pid.0);
+    sys_clone(CloneFlags::empty().bits(), 0)
+}
 ///
 pub fn sys_exec(path: *const u8) -> isize {
     trace!("kernel:pid[{}] sys_exec", current_task().unwrap().pid.0);
@@ -94,10 +110,21 @@ pub fn sys_exec(path: *const u8) -> isize {
     }
 }
 
+bitflags! {
+    /// Options accepted by `sys_waitpid`, mirroring Linux's `wait4(2)` bits.
+    pub struct WaitOption: u32 {
+        /// Return 0 instead of -2 if a matching child exists but none has
+        /// exited yet.
+        const WNOHANG = 1;
+    }
+}
+
 /// If there is not a child process whose pid is same as given, return -1.
-/// Else if there is a child process but it is still running, return -2.
-pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+/// Else if there is a child process but it is still running, return -2
+/// (or, with `WNOHANG` set, 0 instead). The call never blocks either way.
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32, options: u32) -> isize {
     //trace!("kernel: sys_waitpid");
+    let options = WaitOption::from_bits_truncate(options);
     let task = current_task().unwrap();
     // find a child process
 
@@ -122,10 +149,18 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.getpid();
         // ++++ temporarily access child PCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
+        let child_inner = child.inner_exclusive_access();
+        let exit_code = child_inner.exit_code;
+        // Fold the reaped child's CPU time into the parent's own, so
+        // `sys_getrusage`/`query_current_task_run_time` can honor their
+        // documented "current task and its reaped children" total.
+        inner.run_time += child_inner.run_time;
+        drop(child_inner);
         // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
+        *translated_refmut(inner.memory_set.exclusive_access().token(), exit_code_ptr) = exit_code;
         found_pid as isize
+    } else if options.contains(WaitOption::WNOHANG) {
+        0
     } else {
         -2
     }
@@ -165,12 +200,11 @@ pub fn sys_task_info(_ti: *mut TaskInfo) -> isize {
 
     let current_task_status: TaskStatus = query_current_task_status();
     let current_task_syscall_times : [u32; MAX_SYSCALL_NUM] = query_current_task_syscall_times();
-    let current_task_first_run_time : usize = query_current_task_first_run_time();
 
     let task_info = TaskInfo {
         status: current_task_status,
         syscall_times : current_task_syscall_times,
-        time : get_time_ms() - current_task_first_run_time,
+        time : query_current_task_cpu_time(),
     };
     let mut task_info_ptr = &task_info as *const _ as *const u8;
     for buffer in buffers {
@@ -235,33 +269,102 @@ pub fn sys_spawn(_path: *const u8) -> isize {
 
 /// YOUR JOB: Set task priority.
 pub fn sys_set_priority(_prio: isize) -> isize {
-    if _prio < 2 {
-        return -1;
-    }
-
-    let task = current_task().unwrap();
-    let mut inner = task.inner_exclusive_access();
-
-    inner.priority = _prio;
-    inner.pass = BIG_STRIDE / inner.priority;
-
-    _prio
+    set_priority(_prio)
 }
 
 
 /// 添加一个硬链接
-pub fn sys_linkat(olddirfd: i32, oldpath: *const u8, newdirfd: i32, newpath: *const u8, flags: u32) -> i32 {
-    linkat(olddirfd, oldpath, newdirfd, newpath, flags)
+pub fn sys_linkat(_olddirfd: i32, oldpath: *const u8, _newdirfd: i32, newpath: *const u8, _flags: u32) -> i32 {
+    let token = current_user_token();
+    let old_path = translated_str(token, oldpath);
+    let new_path = translated_str(token, newpath);
+    fs::linkat(old_path.as_str(), new_path.as_str()) as i32
 }
 
 
 /// 删除一个硬链接
-pub fn sys_unlinkat(dirfd: i32, path: *const u8, flags: u32) -> i32 {
-
+pub fn sys_unlinkat(_dirfd: i32, path: *const u8, _flags: u32) -> i32 {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    fs::unlinkat(path.as_str()) as i32
 }
 
 /// 获取一个状态
 pub fn sys_fstat(fd: i32, st: *mut Stat) -> i32 {
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let fd_table = inner.fd_table.exclusive_access();
+    if fd as usize >= fd_table.len() {
+        return -1;
+    }
+    let file = match &fd_table[fd as usize] {
+        Some(file) => file.clone(),
+        None => return -1,
+    };
+    drop(fd_table);
+    drop(inner);
+
+    let stat = file.stat();
+    let buffers = translated_byte_buffer(token, st as *const u8, size_of::<Stat>());
+    let mut stat_ptr = &stat as *const _ as *const u8;
+    for buffer in buffers {
+        unsafe {
+            stat_ptr.copy_to(buffer.as_mut_ptr(), buffer.len());
+            stat_ptr = stat_ptr.add(buffer.len());
+        }
+    }
+    0
+}
+
+/// `who` values accepted by `sys_getrusage`, mirroring Linux's
+/// `getrusage(2)`. Only the calling task itself is supported.
+pub const RUSAGE_SELF: i32 = 0;
 
+/// Resource usage information, as reported by `sys_getrusage`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RUsage {
+    /// User CPU time accumulated by the task (and its reaped children).
+    pub ru_utime: TimeVal,
+    /// System CPU time; this kernel doesn't distinguish it from user time,
+    /// so it is always zero.
+    pub ru_stime: TimeVal,
+}
+
+impl RUsage {
+    /// An all-zero `RUsage`.
+    pub fn new() -> Self {
+        Self {
+            ru_utime: TimeVal { sec: 0, usec: 0 },
+            ru_stime: TimeVal { sec: 0, usec: 0 },
+        }
+    }
+}
+
+/// Report the accumulated CPU time of the current task, drawing on the
+/// running total kept in `run_time` (accumulated by `record_run_time` in
+/// `task::processor` as the task is switched away, not by `schedule` itself).
+pub fn sys_getrusage(who: i32, ru: *mut RUsage) -> isize {
+    if who != RUSAGE_SELF {
+        return -1;
+    }
+    let token = current_user_token();
+    let run_time_us = query_current_task_run_time();
+    let mut rusage = RUsage::new();
+    rusage.ru_utime = TimeVal {
+        sec: run_time_us / 1_000_000,
+        usec: run_time_us % 1_000_000,
+    };
+
+    let buffers = translated_byte_buffer(token, ru as *const u8, size_of::<RUsage>());
+    let mut rusage_ptr = &rusage as *const _ as *const u8;
+    for buffer in buffers {
+        unsafe {
+            rusage_ptr.copy_to(buffer.as_mut_ptr(), buffer.len());
+            rusage_ptr = rusage_ptr.add(buffer.len());
+        }
+    }
+    0
 }
 
@@ -1,55 +1,58 @@
 //!Implementation of [`TaskManager`]
+use super::scheduler::{FifoScheduler, Scheduler, StrideScheduler};
 use super::TaskControlBlock;
 use crate::sync::UPSafeCell;
-//use crate::config::BIG_STRIDE;
-//use alloc::collections::VecDeque;
-use alloc::vec::Vec;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
-///A array of `TaskControlBlock` that is thread-safe
+
+/// Which [`Scheduler`] policy `TaskManager::new` boots with. There's no
+/// `config` entry for this in this source tree, so it's a local toggle
+/// instead of an external setting; flip it to exercise `FifoScheduler`.
+const USE_FIFO_SCHEDULER: bool = false;
+
+/// The ready queue, backed by a pluggable [`Scheduler`] policy so the kernel
+/// can swap FIFO, stride, or future priority schemes without touching
+/// `add_task`/`fetch_task`.
 pub struct TaskManager {
-    ready_queue: Vec<Arc<TaskControlBlock>>,
+    scheduler: Box<dyn Scheduler<Arc<TaskControlBlock>> + Send + Sync>,
 }
 
-/// A simple FIFO scheduler.
 impl TaskManager {
-    ///Creat an empty TaskManager
+    /// Create a `TaskManager` using whichever policy [`USE_FIFO_SCHEDULER`]
+    /// selects.
     pub fn new() -> Self {
-        Self {
-            ready_queue: Vec::new(),
+        if USE_FIFO_SCHEDULER {
+            Self::with_scheduler(Box::new(FifoScheduler::new()))
+        } else {
+            Self::with_scheduler(Box::new(StrideScheduler::new()))
         }
     }
+    /// Create a `TaskManager` backed by a caller-chosen scheduler, so the
+    /// policy can be selected at boot.
+    pub fn with_scheduler(scheduler: Box<dyn Scheduler<Arc<TaskControlBlock>> + Send + Sync>) -> Self {
+        Self { scheduler }
+    }
     /// Add process back to ready queue
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        //self.ready_queue.push_back(task)
-        
-        let mut inner = task.inner_exclusive_access();
-        inner.stride += inner.pass;
-        drop(inner);
-        self.ready_queue.push(task);
-        
+        self.scheduler.insert(task);
     }
     /// Take a process out of the ready queue
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        
-        let mut minv = isize::MAX;
-        let mut minv_idx : Option<usize> = None;
-
-        for (idx, item) in self.ready_queue.iter().enumerate() {
-            let inner = item.inner_exclusive_access();
-            if inner.stride < minv {
-                minv_idx = Some(idx);
-                minv = inner.stride;
-            }
-            drop(inner);
-        }
-        
-        if let Some(idx) = minv_idx {
-            let target = self.ready_queue.remove(idx);
-            Some(target)
-        } else {
-            None
-        }
+        self.scheduler.pop()
+    }
+    /// Look at the task that would run next and give `f` a chance to mutate
+    /// it, re-sorting the ready queue afterward if the scheduling policy
+    /// needs to. Returns `false` if the ready queue is empty.
+    pub fn peek_mut_with(&mut self, f: &mut dyn FnMut(&Arc<TaskControlBlock>)) -> bool {
+        self.scheduler.peek_mut_with(f)
+    }
+    /// Remove a specific task from the ready queue, e.g. one that was killed
+    /// before it ever ran.
+    pub fn remove(&mut self, task: &Arc<TaskControlBlock>) -> Option<Arc<TaskControlBlock>> {
+        self.scheduler.remove(task)
     }
 }
 
@@ -69,4 +72,42 @@ pub fn add_task(task: Arc<TaskControlBlock>) {
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
     //trace!("kernel: TaskManager::fetch_task");
     TASK_MANAGER.exclusive_access().fetch()
-}
\ No newline at end of file
+}
+
+/// Remove a specific task from the ready queue
+pub fn remove_task(task: &Arc<TaskControlBlock>) -> Option<Arc<TaskControlBlock>> {
+    TASK_MANAGER.exclusive_access().remove(task)
+}
+
+/// Look at the task that would run next and give `f` a chance to mutate it,
+/// re-sorting the ready queue afterward if the scheduling policy needs to.
+/// Returns `false` if the ready queue is empty.
+pub fn peek_mut_task(f: &mut dyn FnMut(&Arc<TaskControlBlock>)) -> bool {
+    TASK_MANAGER.exclusive_access().peek_mut_with(f)
+}
+
+lazy_static! {
+    /// Tasks descheduled by `block_current_task`, keyed by the resource id
+    /// they're waiting on (e.g. a pipe's ring-buffer address), until
+    /// `wakeup` moves them back onto the ready queue.
+    static ref WAIT_QUEUES: UPSafeCell<BTreeMap<usize, Vec<Arc<TaskControlBlock>>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// Park `task` on the wait queue for `resource_id`.
+pub fn block_task(resource_id: usize, task: Arc<TaskControlBlock>) {
+    WAIT_QUEUES
+        .exclusive_access()
+        .entry(resource_id)
+        .or_insert_with(Vec::new)
+        .push(task);
+}
+
+/// Take every task waiting on `resource_id` off the wait queue, so the
+/// caller can move them back onto the ready queue.
+pub fn take_waiters(resource_id: usize) -> Vec<Arc<TaskControlBlock>> {
+    WAIT_QUEUES
+        .exclusive_access()
+        .remove(&resource_id)
+        .unwrap_or_default()
+}
@@ -7,14 +7,50 @@
 use super::__switch;
 use super::{fetch_task, TaskStatus};
 use super::{TaskContext, TaskControlBlock};
+use crate::sbi::shutdown;
 use crate::sync::UPSafeCell;
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
+use riscv::asm::wfi;
 use crate::mm::{VirtAddr};
 use crate::mm::MapPermission;
 use crate::config::MAX_SYSCALL_NUM;
 use crate::timer::get_time_us;
+
+/// How many tasks currently exist (created but not yet exited), so
+/// `run_tasks` can tell an empty ready queue that's merely waiting on
+/// `Blocked` tasks apart from one where every task is actually done.
+static ALIVE_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Record that a new task now exists. Called once per task from
+/// `TaskControlBlock::new`/`clone_task`.
+pub fn register_task() {
+    ALIVE_TASKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record that a task has exited. Called from `exit_current_and_run_next`.
+pub fn unregister_task() {
+    ALIVE_TASKS.fetch_sub(1, Ordering::Relaxed);
+}
+
+fn all_tasks_exited() -> bool {
+    ALIVE_TASKS.load(Ordering::Relaxed) == 0
+}
+
+/// A single mapped virtual-memory region created via `sys_mmap`, recorded on
+/// the owning task (`inner.mmap_areas`, kept sorted by `start_vpn`) so
+/// `sys_munmap` can tell exactly what is and isn't currently mapped.
+pub struct MapArea {
+    /// First virtual page number covered by this region.
+    pub start_vpn: usize,
+    /// One past the last virtual page number covered by this region.
+    pub end_vpn: usize,
+    /// Access permissions the region was mapped with.
+    pub permission: MapPermission,
+}
 /// Processor management structure
 pub struct Processor {
     ///The task currently executing on the current processor
@@ -60,12 +96,12 @@ pub fn run_tasks() {
     loop {
         let mut processor = PROCESSOR.exclusive_access();
         if let Some(task) = fetch_task() {
-            record_first_switch(task.clone());
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
             // access coming task TCB exclusively
             let mut task_inner = task.inner_exclusive_access();
             let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
             task_inner.task_status = TaskStatus::Running;
+            task_inner.last_switch_in = get_time_us();
             // release coming task_inner manually
             drop(task_inner);
             // release coming task TCB manually
@@ -76,7 +112,18 @@ pub fn run_tasks() {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
         } else {
-            warn!("no tasks available in run_tasks");
+            drop(processor);
+            if all_tasks_exited() {
+                println!("[kernel] all tasks exited, shutting down");
+                shutdown();
+            }
+            // Nothing is `Ready`, but some task is merely `Blocked` on a
+            // resource and will be woken up by an interrupt; idle instead
+            // of busy-spinning until `wakeup` puts it back on the ready
+            // queue.
+            unsafe {
+                wfi();
+            }
         }
     }
 }
@@ -111,12 +158,6 @@ pub fn query_current_task_status() -> TaskStatus {
     let task = binding.inner_exclusive_access();
     task.task_info.status
 }
-/// 公共接口，查询当前任务第一次运行的时间
-pub fn query_current_task_first_run_time() -> usize {
-    let binding = current_task().unwrap();
-    let task = binding.inner_exclusive_access();
-    task.task_info.time
-}
 /// 公共接口，查询当前任务系统调用的次数
 pub fn query_current_task_syscall_times() -> [u32; MAX_SYSCALL_NUM] {
     let binding = current_task().unwrap();
@@ -130,51 +171,159 @@ pub fn add_current_task_syscall_time(syscall_id: usize) {
     task.task_info.syscall_times[syscall_id] += 1;
 }
 
-/// 记录第一次运行的时间
-pub fn record_first_switch(task: Arc<TaskControlBlock>) {
-    let mut inner = task.inner_exclusive_access();
-    if inner.task_info.time == 0 {
-        let time: usize = get_time_us();
-        inner.task_info.time = time;
-    }
+/// 公共接口，查询当前任务（含已回收子进程）累计占用 CPU 的微秒数，供
+/// `sys_getrusage` 使用
+pub fn query_current_task_run_time() -> usize {
+    let binding = current_task().unwrap();
+    let task = binding.inner_exclusive_access();
+    task.run_time
+}
+
+/// 公共接口，查询当前任务累计占用 CPU 的毫秒数，供 `sys_task_info` 使用。
+pub fn query_current_task_cpu_time() -> usize {
+    query_current_task_run_time() / 1_000
 }
 
 /// 申请内存
-pub fn user_allocate_new_space(start: usize, len:usize, port:usize) -> isize {
+///
+/// Rejects a range that overlaps any region the task already has mapped,
+/// recording the new `MapArea` (kept sorted by `start_vpn`) so `munmap` can
+/// later tell what is and isn't actually mapped.
+pub fn user_allocate_new_space(start: usize, len: usize, port: usize) -> isize {
+    if len == 0 {
+        return 0;
+    }
     if port & !0x7 != 0 {
         return -1;
     }
-    if port & 0x7 == 0{
+    if port & 0x7 == 0 {
         return -1;
     }
-    let va_start : VirtAddr= start.into();
+    let va_start: VirtAddr = start.into();
     if !va_start.aligned() {
         return -1;
     }
+    let start_vpn = va_start.floor().0;
+    let end_vpn = VirtAddr::from(start + len).ceil().0;
+
     let mut permissions = MapPermission::empty();
     permissions.set(MapPermission::R, port & 0x1 != 0);
     permissions.set(MapPermission::W, port & 0x2 != 0);
     permissions.set(MapPermission::X, port & 0x4 != 0);
     permissions.set(MapPermission::U, true);
-        
+
     // 获得应用程序的空间
     let task = current_task().unwrap();
     let mut inner = task.inner_exclusive_access();
-    inner.memory_set.allocate_new_space(VirtAddr::from(start), len, permissions)
+    if inner
+        .mmap_areas
+        .iter()
+        .any(|area| start_vpn < area.end_vpn && area.start_vpn < end_vpn)
+    {
+        // overlaps an existing mapping
+        return -1;
+    }
+    let ret = inner
+        .memory_set
+        .exclusive_access()
+        .allocate_new_space(va_start, len, permissions);
+    if ret != 0 {
+        return ret;
+    }
+    let idx = inner
+        .mmap_areas
+        .partition_point(|area| area.start_vpn < start_vpn);
+    inner.mmap_areas.insert(
+        idx,
+        MapArea {
+            start_vpn,
+            end_vpn,
+            permission: permissions,
+        },
+    );
+    0
 }
 /// 回收一个空间
-pub fn user_deallocate_space(start:usize, _len:usize) -> isize {
-    let va_start : VirtAddr = start.into();
+///
+/// Succeeds only if `[start, start + len)` is entirely covered by existing
+/// mappings; regions that only partially overlap the unmap range are split
+/// or truncated so their untouched prefix/suffix stays mapped.
+pub fn user_deallocate_space(start: usize, len: usize) -> isize {
+    if len == 0 {
+        return 0;
+    }
+    let va_start: VirtAddr = start.into();
     if !va_start.aligned() {
         return -1;
     }
+    let start_vpn = va_start.floor().0;
+    let end_vpn = VirtAddr::from(start + len).ceil().0;
+
     // 获得应用程序的空间
     let task = current_task().unwrap();
     let mut inner = task.inner_exclusive_access();
-    inner.memory_set.deallocate_space(VirtAddr::from(start), _len)
+
+    let covered: usize = inner
+        .mmap_areas
+        .iter()
+        .map(|area| {
+            let lo = area.start_vpn.max(start_vpn);
+            let hi = area.end_vpn.min(end_vpn);
+            hi.saturating_sub(lo)
+        })
+        .sum();
+    if covered != end_vpn - start_vpn {
+        // some part of the requested range was never mapped
+        return -1;
+    }
+
+    let ret = inner.memory_set.exclusive_access().deallocate_space(va_start, len);
+    if ret != 0 {
+        return ret;
+    }
+
+    let mut remaining = Vec::with_capacity(inner.mmap_areas.len());
+    for area in core::mem::take(&mut inner.mmap_areas) {
+        if area.end_vpn <= start_vpn || area.start_vpn >= end_vpn {
+            // entirely outside the unmap range, left untouched
+            remaining.push(area);
+            continue;
+        }
+        if area.start_vpn < start_vpn {
+            remaining.push(MapArea {
+                start_vpn: area.start_vpn,
+                end_vpn: start_vpn,
+                permission: area.permission,
+            });
+        }
+        if area.end_vpn > end_vpn {
+            remaining.push(MapArea {
+                start_vpn: end_vpn,
+                end_vpn: area.end_vpn,
+                permission: area.permission,
+            });
+        }
+    }
+    inner.mmap_areas = remaining;
+    0
 }
 
 
+/// Accumulate the time `task` just spent running (the delta since its
+/// `last_switch_in`) into `run_time`, so `query_current_task_run_time`/
+/// `sys_getrusage` report real CPU usage rather than only the
+/// first-dispatch timestamp.
+///
+/// Must be called by whoever is switching `task` away *while they still
+/// hold its `Arc`* — by the time it reaches `schedule`, `take_current_task`
+/// has already cleared `Processor::current`, so reading it back there would
+/// always see `None`.
+pub fn record_run_time(task: &Arc<TaskControlBlock>) {
+    let mut inner = task.inner_exclusive_access();
+    let now = get_time_us();
+    inner.run_time += now.saturating_sub(inner.last_switch_in);
+}
+
 ///Return to idle control flow for new scheduling
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
     let mut processor = PROCESSOR.exclusive_access();
@@ -0,0 +1,175 @@
+//! Pluggable scheduling policies for the ready queue
+//!
+//! [`Scheduler`] abstracts away the data structure and ordering used to pick
+//! the next ready task, so [`TaskManager`](super::manager::TaskManager) can
+//! swap FIFO, stride, or future priority policies without touching
+//! `add_task`/`fetch_task`.
+
+use super::TaskControlBlock;
+use alloc::collections::BinaryHeap;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+/// A queue of ready tasks ordered by some scheduling policy.
+pub trait Scheduler<T> {
+    /// Insert a task into the queue.
+    fn insert(&mut self, task: T);
+    /// Peek at the task that would run next, without removing it.
+    fn peek(&self) -> Option<&T>;
+    /// Peek at the task that would run next and give `f` a look at it,
+    /// re-sorting the backing structure afterward in case `f` mutated state
+    /// (e.g. `stride`/`priority` through the task's own interior mutability)
+    /// that the scheduling order depends on. Returns `false` if the queue was
+    /// empty.
+    ///
+    /// This exists instead of a bare `peek_mut(&mut self) -> Option<&mut T>`
+    /// because `StrideScheduler` can only re-sift its heap correctly if the
+    /// mutation happens while its `BinaryHeap::PeekMut` guard is still alive;
+    /// handing back a plain `&mut T` would let the guard drop (and re-sift)
+    /// before the caller gets a chance to mutate anything.
+    fn peek_mut_with(&mut self, f: &mut dyn FnMut(&T)) -> bool;
+    /// Remove and return the task that should run next.
+    fn pop(&mut self) -> Option<T>;
+    /// Remove a specific task from the queue, e.g. one that was killed
+    /// before it ran. Returns the removed task, if it was present.
+    fn remove(&mut self, task: &T) -> Option<T>;
+}
+
+/// A plain first-in-first-out scheduler.
+pub struct FifoScheduler {
+    queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl FifoScheduler {
+    /// Create an empty FIFO scheduler.
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler<Arc<TaskControlBlock>> for FifoScheduler {
+    fn insert(&mut self, task: Arc<TaskControlBlock>) {
+        self.queue.push_back(task);
+    }
+    fn peek(&self) -> Option<&Arc<TaskControlBlock>> {
+        self.queue.front()
+    }
+    fn peek_mut_with(&mut self, f: &mut dyn FnMut(&Arc<TaskControlBlock>)) -> bool {
+        // FIFO order never depends on task state, so no re-sort is needed.
+        match self.queue.front() {
+            Some(task) => {
+                f(task);
+                true
+            }
+            None => false,
+        }
+    }
+    fn pop(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.queue.pop_front()
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> Option<Arc<TaskControlBlock>> {
+        let idx = self.queue.iter().position(|t| Arc::ptr_eq(t, task))?;
+        self.queue.remove(idx)
+    }
+}
+
+/// A heap entry ordered by `stride` so that popping the max of the heap
+/// yields the ready task with the (wrapping-)smallest `stride`.
+///
+/// Strides are `isize`s advanced with `wrapping_add`, so they wrap around
+/// `isize::MAX` and a naive `<` would misorder a task
+/// right after it overflows. Since every `pass` is at most `BIG_STRIDE / 2`
+/// (priority is always `>= 2`), the signed wrapping difference between two
+/// strides still tells us which one is "behind" and should run next.
+struct StrideEntry(Arc<TaskControlBlock>);
+
+impl StrideEntry {
+    fn stride(&self) -> isize {
+        self.0.inner_exclusive_access().stride
+    }
+}
+
+impl PartialEq for StrideEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.stride() == other.stride()
+    }
+}
+
+impl Eq for StrideEntry {}
+
+impl PartialOrd for StrideEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StrideEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // `BinaryHeap` is a max-heap, so reverse the usual comparison: an
+        // entry compares as "greater" exactly when its stride is the one
+        // that is behind (and thus due to run next).
+        let diff = other.stride().wrapping_sub(self.stride());
+        diff.cmp(&0)
+    }
+}
+
+/// The stride scheduling policy: always picks the ready task with the
+/// smallest `stride`, advancing it by its `pass` on every `insert`. Backed
+/// by a binary heap so picking the next task is `O(log n)` instead of the
+/// full linear scan a `Vec` would need.
+pub struct StrideScheduler {
+    heap: BinaryHeap<StrideEntry>,
+}
+
+impl StrideScheduler {
+    /// Create an empty stride scheduler.
+    pub fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+        }
+    }
+}
+
+impl Scheduler<Arc<TaskControlBlock>> for StrideScheduler {
+    fn insert(&mut self, task: Arc<TaskControlBlock>) {
+        let mut inner = task.inner_exclusive_access();
+        inner.stride = inner.stride.wrapping_add(inner.pass);
+        drop(inner);
+        self.heap.push(StrideEntry(task));
+    }
+    fn peek(&self) -> Option<&Arc<TaskControlBlock>> {
+        self.heap.peek().map(|entry| &entry.0)
+    }
+    fn peek_mut_with(&mut self, f: &mut dyn FnMut(&Arc<TaskControlBlock>)) -> bool {
+        // Keep the `PeekMut` guard alive across `f`, not just across
+        // `self.heap.peek_mut()` itself, so that if `f` mutates the task's
+        // `stride` (through its own interior mutability) the guard's `Drop`
+        // re-sifts the heap with the new value instead of leaving it
+        // sorted by the stale one.
+        match self.heap.peek_mut() {
+            Some(guard) => {
+                f(&guard.0);
+                true
+            }
+            None => false,
+        }
+    }
+    fn pop(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.heap.pop().map(|entry| entry.0)
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> Option<Arc<TaskControlBlock>> {
+        // `BinaryHeap` has no indexed removal, so rebuild it without the
+        // target task. Priority changes go through `sys_set_priority`
+        // instead of `remove`, so this only runs for the rarer "yank a
+        // killed task out of the ready queue" path.
+        let mut items: Vec<StrideEntry> = core::mem::take(&mut self.heap).into_vec();
+        let idx = items.iter().position(|entry| Arc::ptr_eq(&entry.0, task))?;
+        let removed = items.remove(idx);
+        self.heap = items.into_iter().collect();
+        Some(removed.0)
+    }
+}
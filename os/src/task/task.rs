@@ -1,21 +1,426 @@
 //! Types related to task management
 
+use super::processor::MapArea;
 use super::TaskContext;
-use crate::{
-    config::MAX_SYSCALL_NUM,
+use crate::config::{
+    kernel_stack_position, BIG_STRIDE, MAX_SYSCALL_NUM, PAGE_SIZE, TIME_SLICE, TRAP_CONTEXT,
 };
+use crate::fs::{File, Stdin, Stdout};
+use crate::mm::{MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::sync::UPSafeCell;
+use crate::trap::{trap_handler, TrapContext};
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefMut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Hand out a fresh, never-reused pid for every task created, whether by
+/// `TaskControlBlock::new` (`initproc` and `spawn`ed tasks) or `fork`/`clone`.
+fn alloc_pid() -> usize {
+    static NEXT_PID: AtomicUsize = AtomicUsize::new(0);
+    NEXT_PID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// An allocated process id. Wrapped rather than a bare `usize` so a pid
+/// can't be confused with e.g. a fd or a syscall id at a call site.
+pub struct PidHandle(pub usize);
+
+bitflags! {
+    /// Flags controlling what `clone_task` shares between parent and
+    /// child, mirroring the bits Linux's `clone(2)` assigns them.
+    pub struct CloneFlags: u32 {
+        /// Share the parent's address space (`memory_set`) instead of
+        /// copying it, giving parent and child the same page table.
+        const CLONE_VM = 0x0000_0100;
+        /// Share the parent's open file descriptor table.
+        const CLONE_FILES = 0x0000_0400;
+        /// Place the child in the parent's thread group.
+        const CLONE_THREAD = 0x0001_0000;
+    }
+}
+
+/// A task's kernel stack, mapped into `KERNEL_SPACE` at a fixed, pid-indexed
+/// address (see `kernel_stack_position`) so traps into the kernel always
+/// have a valid stack to run on, independent of the task's own page table.
+pub struct KernelStack(usize);
+
+impl KernelStack {
+    fn new(pid: &PidHandle) -> Self {
+        let (bottom, top) = kernel_stack_position(pid.0);
+        KERNEL_SPACE.exclusive_access().insert_framed_area(
+            bottom.into(),
+            top.into(),
+            crate::mm::MapPermission::R | crate::mm::MapPermission::W,
+        );
+        Self(pid.0)
+    }
+
+    /// The stack pointer a brand-new task should start executing with.
+    pub fn get_top(&self) -> usize {
+        let (_, top) = kernel_stack_position(self.0);
+        top
+    }
+}
+
+/// Task information exposed to user space via `sys_task_info`.
+pub struct TaskInfo {
+    /// Task status in its life cycle
+    pub status: TaskStatus,
+    /// The number of times each syscall has been invoked by this task
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+}
+
+impl TaskInfo {
+    /// A freshly-created task has made no syscalls and hasn't run yet.
+    pub fn new() -> Self {
+        Self {
+            status: TaskStatus::Ready,
+            syscall_times: [0; MAX_SYSCALL_NUM],
+        }
+    }
+}
 
 /// The task control block (TCB) of a task.
-#[derive(Copy, Clone)]
+///
+/// `pid` and the kernel stack are fixed for the task's whole lifetime, so
+/// they live directly on the TCB; everything that changes while the task
+/// runs lives behind `inner`'s [`UPSafeCell`], matching how every other PCB
+/// in this kernel is split.
 pub struct TaskControlBlock {
-    /// The task status in it's lifecycle
-    pub task_status: TaskStatus,
-    /// The task context
+    /// Process identifier, stable for the task's whole lifetime.
+    pub pid: PidHandle,
+    /// The kernel stack this task traps into.
+    pub kernel_stack: KernelStack,
+    inner: UPSafeCell<TaskControlBlockInner>,
+}
+
+/// Everything about a task that can change while it's alive.
+pub struct TaskControlBlockInner {
+    /// Physical page holding this task's trap context.
+    pub trap_cx_ppn: PhysPageNum,
+    /// Size, in bytes, of the application plus the stack gap below `TRAP_CONTEXT`.
+    pub base_size: usize,
+    /// The task context, swapped by `__switch`.
     pub task_cx: TaskContext,
-    /// 第一次被调度的时间
-    pub first_reload_time: Option<usize>,
-    /// 当时任务的系统调用及调用的次数
-    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    /// The task status in its lifecycle.
+    pub task_status: TaskStatus,
+    /// The task's address space. `Arc`-shared between every task cloned
+    /// with `CLONE_VM` so they genuinely see the same mappings, rather than
+    /// each holding an independent copy.
+    pub memory_set: Arc<UPSafeCell<MemorySet>>,
+    /// The parent task, if any. A `Weak` reference so a parent/child cycle
+    /// of `Arc`s doesn't leak.
+    pub parent: Option<Weak<TaskControlBlock>>,
+    /// Live children, reparented onto `INITPROC` when this task exits.
+    pub children: Vec<Arc<TaskControlBlock>>,
+    /// Exit code, set by `exit_current_and_run_next` and read by `sys_waitpid`.
+    pub exit_code: i32,
+    /// Open file descriptor table. `Arc`-shared between every task cloned
+    /// with `CLONE_FILES` so `open`/`close`/`dup` on one are visible to the
+    /// other, rather than each holding an independent copy of the `Arc`s.
+    pub fd_table: Arc<UPSafeCell<Vec<Option<Arc<dyn File + Send + Sync>>>>>,
+    /// `mmap`ped regions, kept sorted by `start_vpn`.
+    pub mmap_areas: Vec<MapArea>,
+    /// Current program break (end of the heap), for `sys_sbrk`.
+    pub program_brk: usize,
+    /// Program break at task creation, the lower bound `sys_sbrk` can't cross.
+    pub heap_bottom: usize,
+    /// Bookkeeping surfaced to user space via `sys_task_info`.
+    pub task_info: TaskInfo,
+    /// Timer ticks left in this task's current time slice before it is
+    /// preempted and put back on the ready queue.
+    pub time_slice_remaining: usize,
+    /// Accumulated CPU time, in microseconds, across every dispatch of this
+    /// task plus every child already reaped via `sys_waitpid` (see
+    /// `sys_waitpid` in `syscall::process`).
+    pub run_time: usize,
+    /// Timestamp of the most recent dispatch onto the CPU.
+    pub last_switch_in: usize,
+    /// Stride-scheduling priority, settable via `sys_set_priority`.
+    pub priority: isize,
+    /// Stride increment added to `stride` each time this task is scheduled,
+    /// `BIG_STRIDE / priority`.
+    pub pass: isize,
+    /// Running stride total; the scheduler always dispatches the smallest.
+    pub stride: isize,
+}
+
+impl TaskControlBlockInner {
+    /// The trap context lives in its own page, mapped at a fixed virtual
+    /// address in every task's address space.
+    pub fn get_trap_cx(&self) -> &'static mut TrapContext {
+        self.trap_cx_ppn.get_mut()
+    }
+
+    /// The token (satp value) identifying this task's page table.
+    pub fn get_user_token(&self) -> usize {
+        self.memory_set.exclusive_access().token()
+    }
+
+    /// A task is a zombie once it has exited but hasn't been reaped yet.
+    pub fn is_zombie(&self) -> bool {
+        self.task_status == TaskStatus::Exited
+    }
+
+    /// Grow or shrink the heap by `size` bytes, returning the break's value
+    /// before the change. Negative `size` shrinks; a request that would move
+    /// the break below `heap_bottom` is rejected.
+    pub fn change_program_brk(&mut self, size: i32) -> Option<usize> {
+        let old_break = self.program_brk;
+        let new_break = if size >= 0 {
+            old_break.checked_add(size as usize)?
+        } else {
+            old_break.checked_sub((-size) as usize)?
+        };
+        if new_break < self.heap_bottom {
+            return None;
+        }
+        let mut memory_set = self.memory_set.exclusive_access();
+        let result = if size >= 0 {
+            memory_set.append_to(VirtAddr::from(old_break), VirtAddr::from(new_break))
+        } else {
+            memory_set.shrink_to(VirtAddr::from(old_break), VirtAddr::from(new_break))
+        };
+        drop(memory_set);
+        if result {
+            self.program_brk = new_break;
+            Some(old_break)
+        } else {
+            None
+        }
+    }
+}
+
+impl TaskControlBlock {
+    /// Exclusive access to the mutable part of the PCB, same pattern every
+    /// `UPSafeCell`-backed shared state in this kernel uses.
+    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
+        self.inner.exclusive_access()
+    }
+
+    /// The token (satp value) identifying this task's page table.
+    pub fn get_user_token(&self) -> usize {
+        self.inner_exclusive_access().get_user_token()
+    }
+
+    /// This task's process id.
+    pub fn getpid(&self) -> usize {
+        self.pid.0
+    }
+
+    /// Grow or shrink the heap by `size` bytes; see
+    /// [`TaskControlBlockInner::change_program_brk`].
+    pub fn change_program_brk(&self, size: i32) -> Option<usize> {
+        self.inner_exclusive_access().change_program_brk(size)
+    }
+
+    /// Build a task straight from an ELF image: a fresh address space, a
+    /// fresh pid and kernel stack, default stdio fds, and no parent. Used
+    /// for `initproc` and by `sys_spawn`, which sets the parent afterward.
+    pub fn new(elf_data: &[u8]) -> Self {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+        let pid_handle = PidHandle(alloc_pid());
+        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack_top = kernel_stack.get_top();
+        let task_control_block = Self {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: user_sp,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set: Arc::new(unsafe { UPSafeCell::new(memory_set) }),
+                    parent: None,
+                    children: Vec::new(),
+                    exit_code: 0,
+                    fd_table: Arc::new(unsafe {
+                        UPSafeCell::new(vec![
+                            Some(Arc::new(Stdin) as Arc<dyn File + Send + Sync>),
+                            Some(Arc::new(Stdout)),
+                            Some(Arc::new(Stdout)),
+                        ])
+                    }),
+                    mmap_areas: Vec::new(),
+                    program_brk: user_sp,
+                    heap_bottom: user_sp,
+                    task_info: TaskInfo::new(),
+                    time_slice_remaining: TIME_SLICE,
+                    run_time: 0,
+                    last_switch_in: 0,
+                    priority: 16,
+                    pass: BIG_STRIDE / 16,
+                    stride: 0,
+                })
+            },
+        };
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+        super::processor::register_task();
+        task_control_block
+    }
+
+    /// Replace this task's address space and trap context in place,
+    /// keeping its pid, kernel stack, and parent/children. Used by
+    /// `sys_exec`.
+    pub fn exec(&self, elf_data: &[u8]) {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .unwrap()
+            .ppn();
+
+        let mut inner = self.inner_exclusive_access();
+        // A fresh `Arc` instead of mutating through the old one: `exec`
+        // always gets an address space of its own, even if this task was
+        // previously sharing one via `CLONE_VM` (matching real `exec(2)`,
+        // which tears a process's other threads down first).
+        inner.memory_set = Arc::new(unsafe { UPSafeCell::new(memory_set) });
+        inner.trap_cx_ppn = trap_cx_ppn;
+        inner.base_size = user_sp;
+        inner.program_brk = user_sp;
+        inner.heap_bottom = user_sp;
+        inner.mmap_areas.clear();
+        let kernel_stack_top = self.kernel_stack.get_top();
+        let trap_cx = inner.get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+    }
+
+    /// Create a child task, sharing address space / fd table / thread-group
+    /// membership with `self` according to `flags` instead of always
+    /// copying them the way plain `fork` does.
+    pub fn clone_task(self: &Arc<Self>, flags: CloneFlags) -> Arc<Self> {
+        let mut parent_inner = self.inner_exclusive_access();
+        let pid_handle = PidHandle(alloc_pid());
+
+        // `CLONE_VM` genuinely shares the parent's address space (the same
+        // `Arc<UPSafeCell<MemorySet>>`, not a copy of its mappings) so the
+        // two tasks are real threads. Since they'd otherwise collide on the
+        // single `TRAP_CONTEXT` page, the child's trap context is mapped at
+        // a fresh, pid-indexed virtual page within that shared space instead
+        // of reusing the parent's.
+        //
+        // Known limitation: `trap_return` always restores from the fixed
+        // `TRAP_CONTEXT` VA, not from this task's own `trap_cx_ppn`, so this
+        // per-thread page is only ever observed kernel-side (`get_trap_cx`,
+        // the `x[10]`/`x[2]` fixups below) — the assembly trap/return path
+        // still resumes through the single VA shared by the whole address
+        // space. Giving CLONE_VM threads an independently *resumable* trap
+        // context would need the trap subsystem itself to take the target
+        // VA as a parameter (as upstream rCore-tutorial's thread branch
+        // does); that subsystem isn't part of this source tree to change.
+        // We still seed this page from the parent's live context (instead
+        // of leaving it zeroed) so kernel-side reads see valid register
+        // state rather than a null pc/page table.
+        let (memory_set, trap_cx_ppn) = if flags.contains(CloneFlags::CLONE_VM) {
+            let trap_cx_bottom = TRAP_CONTEXT - pid_handle.0 * PAGE_SIZE;
+            let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
+            parent_inner.memory_set.exclusive_access().insert_framed_area(
+                trap_cx_bottom.into(),
+                trap_cx_top.into(),
+                crate::mm::MapPermission::R | crate::mm::MapPermission::W,
+            );
+            let trap_cx_ppn = parent_inner
+                .memory_set
+                .exclusive_access()
+                .translate(VirtAddr::from(trap_cx_bottom).into())
+                .unwrap()
+                .ppn();
+            let new_trap_cx: &'static mut TrapContext = trap_cx_ppn.get_mut();
+            // Seed from the parent's live context instead of leaving this
+            // page zeroed, so a child that somehow does resume through it
+            // doesn't jump to a null pc with a null page table.
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    parent_inner.get_trap_cx() as *const TrapContext,
+                    new_trap_cx as *mut TrapContext,
+                    1,
+                );
+            }
+            (Arc::clone(&parent_inner.memory_set), trap_cx_ppn)
+        } else {
+            let parent_memory_set = parent_inner.memory_set.exclusive_access();
+            let memory_set = MemorySet::from_existing_user(&parent_memory_set);
+            drop(parent_memory_set);
+            let trap_cx_ppn = memory_set
+                .translate(VirtAddr::from(TRAP_CONTEXT).into())
+                .unwrap()
+                .ppn();
+            (Arc::new(unsafe { UPSafeCell::new(memory_set) }), trap_cx_ppn)
+        };
+
+        // `CLONE_FILES` shares the same fd table `Arc`, so `open`/`close`/
+        // `dup` on one task are visible to the other; otherwise the child
+        // gets its own table seeded with the parent's current fds.
+        let fd_table = if flags.contains(CloneFlags::CLONE_FILES) {
+            Arc::clone(&parent_inner.fd_table)
+        } else {
+            let cloned = parent_inner
+                .fd_table
+                .exclusive_access()
+                .iter()
+                .map(|fd| fd.as_ref().map(Arc::clone))
+                .collect();
+            Arc::new(unsafe { UPSafeCell::new(cloned) })
+        };
+
+        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack_top = kernel_stack.get_top();
+        let task_control_block = Arc::new(Self {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: parent_inner.base_size,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set,
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    exit_code: 0,
+                    fd_table,
+                    mmap_areas: Vec::new(),
+                    program_brk: parent_inner.program_brk,
+                    heap_bottom: parent_inner.heap_bottom,
+                    task_info: TaskInfo::new(),
+                    time_slice_remaining: TIME_SLICE,
+                    run_time: 0,
+                    last_switch_in: 0,
+                    priority: parent_inner.priority,
+                    pass: parent_inner.pass,
+                    stride: parent_inner.stride,
+                })
+            },
+        });
+        // `CLONE_THREAD` places the child in the parent's thread group
+        // rather than making it an independent, `waitpid`-able child.
+        if !flags.contains(CloneFlags::CLONE_THREAD) {
+            parent_inner.children.push(task_control_block.clone());
+        }
+        let trap_cx = task_control_block.inner_exclusive_access().get_trap_cx();
+        trap_cx.kernel_sp = kernel_stack_top;
+        super::processor::register_task();
+        task_control_block
+    }
 }
 
 /// The status of a task
@@ -27,6 +432,9 @@ pub enum TaskStatus {
     Ready,
     /// running
     Running,
+    /// descheduled while waiting on a resource (e.g. slow I/O); see
+    /// `block_current_task`/`wakeup` in `task::mod`
+    Blocked,
     /// exited
     Exited,
 }